@@ -1,19 +1,227 @@
+extern crate deflate;
+
 use std::io::{Seek, SeekFrom, Write, self};
 
+use deflate::Compression;
+use deflate::write::ZlibEncoder;
 
 pub struct Pdf<'a, W: 'a + Write + Seek> {
     output: &'a mut W,
-    object_offsets: Vec<i64>,
+    object_offsets: Vec<ObjectLocation>,
     page_objects_ids: Vec<usize>,
+    compress: bool,
+    info: Option<Info>,
+    use_xref_streams: bool,
+    // Dictionary objects (id, raw `<< ... >>` bytes) waiting to be packed into
+    // an `/Type /ObjStm` object stream, when `use_xref_streams` is set.
+    pending_objstm: Vec<(usize, Vec<u8>)>,
+}
+
+/// Where an object's data lives, for the cross-reference table/stream.
+#[derive(Clone, Copy)]
+enum ObjectLocation {
+    /// Not written yet.
+    Unwritten,
+    /// A classic object at this byte offset in the file.
+    Offset(u64),
+    /// Object `index` inside the `/Type /ObjStm` object numbered `stream_object_id`.
+    Compressed { stream_object_id: usize, index: usize },
 }
 
 pub struct Canvas<'a, W: 'a + Write> {
     output: &'a mut W,
+    fonts_used: Vec<usize>,
+    current_font: Option<(usize, f32)>,
+}
+
+/// A font embedded in a `Pdf` document, returned by `Pdf::add_font`.
+///
+/// Pass a reference to `Canvas::set_font` to select it before drawing text.
+#[derive(Clone, Copy)]
+pub struct Font {
+    object_id: usize,
+}
+
+/// Document metadata, set via `Pdf::set_info` and written as the `/Info`
+/// dictionary (and an XMP `/Metadata` stream on the catalog).
+#[derive(Clone, Default)]
+pub struct Info {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub keywords: Option<String>,
+    pub creator: Option<String>,
+    pub producer: Option<String>,
+    pub creation_date: Option<Date>,
+    pub mod_date: Option<Date>,
+}
+
+/// A point in time for `Info::creation_date` and `Info::mod_date`.
+///
+/// `tz_offset_minutes` is the offset from UTC in minutes, e.g. `60` for UTC+1.
+#[derive(Clone, Copy)]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub tz_offset_minutes: i16,
+}
+
+impl Date {
+    /// Format as a PDF date string: `D:YYYYMMDDHHmmSSOHH'mm'`.
+    fn to_pdf_string(&self) -> String {
+        let (sign, offset) = self.split_offset();
+        format!("D:{:04}{:02}{:02}{:02}{:02}{:02}{}{:02}'{:02}'",
+                self.year, self.month, self.day,
+                self.hour, self.minute, self.second,
+                sign, offset / 60, offset % 60)
+    }
+
+    /// Format as an XMP/ISO-8601 date string: `YYYY-MM-DDTHH:MM:SS+HH:MM`.
+    fn to_xmp_string(&self) -> String {
+        let (sign, offset) = self.split_offset();
+        format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}{:02}:{:02}",
+                self.year, self.month, self.day,
+                self.hour, self.minute, self.second,
+                sign, offset / 60, offset % 60)
+    }
+
+    fn split_offset(&self) -> (char, i16) {
+        if self.tz_offset_minutes >= 0 {
+            ('+', self.tz_offset_minutes)
+        } else {
+            ('-', -self.tz_offset_minutes)
+        }
+    }
+}
+
+/// Standard page sizes, for use with `Pdf::render_page_sized`.
+#[derive(Clone, Copy)]
+pub enum PageSize {
+    A3,
+    A4,
+    Letter,
+    Legal,
+}
+
+impl PageSize {
+    /// Width and height in points, portrait orientation.
+    pub fn dimensions(&self) -> (f32, f32) {
+        match *self {
+            PageSize::A3 => (841.89, 1190.55),
+            PageSize::A4 => (595.28, 841.89),
+            PageSize::Letter => (612.0, 792.0),
+            PageSize::Legal => (612.0, 1008.0),
+        }
+    }
+
+    /// Width and height in points, with width and height swapped for a
+    /// landscape layout.
+    pub fn landscape(&self) -> (f32, f32) {
+        let (width, height) = self.dimensions();
+        (height, width)
+    }
+}
+
+/// The winding rule used to decide a path's filled interior.
+#[derive(Clone, Copy)]
+pub enum FillRule {
+    NonZero,
+    EvenOdd,
+}
+
+/// A sequence of subpaths, built with the same vocabulary as `Canvas`’s
+/// `move_to` / `line_to` / `curve_to` / `close_path`, for use in a `Scene`.
+#[derive(Clone, Default)]
+pub struct Path {
+    segments: Vec<PathSegment>,
+}
+
+#[derive(Clone, Copy)]
+enum PathSegment {
+    MoveTo(f32, f32),
+    LineTo(f32, f32),
+    CurveTo(f32, f32, f32, f32, f32, f32),
+    Close,
+}
+
+impl Path {
+    pub fn new() -> Path {
+        Path { segments: vec![] }
+    }
+
+    pub fn move_to(&mut self, x: f32, y: f32) -> &mut Path {
+        self.segments.push(PathSegment::MoveTo(x, y));
+        self
+    }
+
+    pub fn line_to(&mut self, x: f32, y: f32) -> &mut Path {
+        self.segments.push(PathSegment::LineTo(x, y));
+        self
+    }
+
+    pub fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x3: f32, y3: f32) -> &mut Path {
+        self.segments.push(PathSegment::CurveTo(x1, y1, x2, y2, x3, y3));
+        self
+    }
+
+    pub fn close(&mut self) -> &mut Path {
+        self.segments.push(PathSegment::Close);
+        self
+    }
+}
+
+/// How a `Path` in a `Scene` is painted.
+#[derive(Clone, Copy)]
+pub enum Paint {
+    Fill { color: (f32, f32, f32) },
+    Stroke { color: (f32, f32, f32), line_width: f32 },
+    FillAndStroke {
+        fill_color: (f32, f32, f32),
+        stroke_color: (f32, f32, f32),
+        line_width: f32,
+    },
+}
+
+/// One entry of a `Scene`: a path, how to paint it, its winding rule, and an
+/// optional clip path applied (via `W n`) before painting.
+#[derive(Clone)]
+pub struct DisplayItem {
+    pub path: Path,
+    pub paint: Paint,
+    pub fill_rule: FillRule,
+    pub clip: Option<Path>,
+}
+
+/// An in-memory display list rendered in one call to `Pdf::render_scene`.
+pub type Scene = Vec<DisplayItem>;
+
+/// Write the `m`/`l`/`c`/`h` operators for `path` to `canvas`.
+fn apply_path<W: Write>(canvas: &mut Canvas<W>, path: &Path) -> io::Result<()> {
+    for &segment in &path.segments {
+        try!(match segment {
+            PathSegment::MoveTo(x, y) => canvas.move_to(x, y),
+            PathSegment::LineTo(x, y) => canvas.line_to(x, y),
+            PathSegment::CurveTo(x1, y1, x2, y2, x3, y3) => canvas.curve_to(x1, y1, x2, y2, x3, y3),
+            PathSegment::Close => canvas.close_path(),
+        });
+    }
+    Ok(())
 }
 
 const ROOT_OBJECT_ID: usize = 1;
 const PAGES_OBJECT_ID: usize = 2;
 
+// The widths array below covers this range of character codes.
+const FIRST_CHAR: u8 = 32;
+const LAST_CHAR: u8 = 255;
+// TrueType glyph widths are not parsed from the font program yet,
+// so every glyph is given the same approximate width.
+const DEFAULT_GLYPH_WIDTH: u16 = 600;
+
 impl<'a, W: Write + Seek> Pdf<'a, W> {
     pub fn new(output: &'a mut W) -> io::Result<Pdf<'a, W>> {
         // FIXME: Find out the lowest version that contains the features we’re using.
@@ -22,8 +230,12 @@ impl<'a, W: Write + Seek> Pdf<'a, W> {
             output: output,
             // Object ID 0 is special in PDF.
             // We reserve IDs 1 and 2 for the catalog and page tree.
-            object_offsets: vec![-1, -1, -1],
+            object_offsets: vec![ObjectLocation::Unwritten; 3],
             page_objects_ids: vec![],
+            compress: true,
+            info: None,
+            use_xref_streams: false,
+            pending_objstm: vec![],
         })
     }
 
@@ -32,99 +244,422 @@ impl<'a, W: Write + Seek> Pdf<'a, W> {
         self.output.seek(SeekFrom::Current(0))
     }
 
+    /// Toggle `FlateDecode` compression of content streams (on by default).
+    /// Disabling it makes the output easier to inspect for debugging.
+    pub fn set_compression(&mut self, compress: bool) {
+        self.compress = compress;
+    }
+
+    /// Set the document information dictionary, written to `/Info` on
+    /// `finish` along with an XMP `/Metadata` stream on the catalog.
+    pub fn set_info(&mut self, info: Info) {
+        self.info = Some(info);
+    }
+
+    /// Use PDF 1.5 cross-reference streams and compressed object streams
+    /// instead of a classic `xref` table, shrinking the output for documents
+    /// with many small objects. Off by default, for maximum compatibility.
+    pub fn set_cross_reference_streams(&mut self, use_xref_streams: bool) {
+        self.use_xref_streams = use_xref_streams;
+    }
+
     pub fn render_page<F>(&mut self, width: f32, height: f32, render_contents: F) -> io::Result<()>
-    where F: FnOnce(&mut Canvas<W>) -> io::Result<()> {
-        let (contents_object_id, content_length) =
-        try!(self.write_new_object(move |contents_object_id, pdf| {
-            // Guess the ID of the next object. (We’ll assert it below.)
-            try!(write!(pdf.output, "<<  /Length {} 0 R\n", contents_object_id + 1));
-            try!(write!(pdf.output, ">>\n"));
-            try!(write!(pdf.output, "stream\n"));
+    where F: FnOnce(&mut Canvas<Vec<u8>>) -> io::Result<()> {
+        self.render_page_impl(width, height, 0, render_contents)
+    }
 
-            let start = try!(pdf.tell());
-            try!(write!(pdf.output, "/DeviceRGB cs /DeviceRGB CS\n"));
-            try!(write!(pdf.output, "0.75 0 0 -0.75 0 {} cm\n", height));
-            try!(render_contents(&mut Canvas { output: pdf.output }));
-            let end = try!(pdf.tell());
+    /// Render a page whose size in points is `(width, height)`, typically
+    /// obtained from `PageSize::dimensions` or `PageSize::landscape`, with an
+    /// explicit `/Rotate` of `0`, `90`, `180`, or `270` degrees.
+    ///
+    /// Different pages in the same document can use different sizes.
+    pub fn render_page_sized<F>(&mut self, (width, height): (f32, f32), rotate: u16,
+                                 render_contents: F) -> io::Result<()>
+    where F: FnOnce(&mut Canvas<Vec<u8>>) -> io::Result<()> {
+        self.render_page_impl(width, height, rotate, render_contents)
+    }
 
-            try!(write!(pdf.output, "endstream\n"));
-            Ok((contents_object_id, end - start))
-        }));
-        try!(self.write_new_object(|length_object_id, pdf| {
-            assert!(length_object_id == contents_object_id + 1);
-            write!(pdf.output, "{}\n", content_length)
-        }));
-        let page_object_id = try!(self.write_new_object(|page_object_id, pdf| {
-            try!(write!(pdf.output, "<<  /Type /Page\n"));
-            try!(write!(pdf.output, "    /Parent {} 0 R\n", PAGES_OBJECT_ID));
-            try!(write!(pdf.output, "    /Resources << >>\n"));
-            try!(write!(pdf.output, "    /MediaBox [ 0 0 {} {} ]\n", width, height));
-            try!(write!(pdf.output, "    /Contents {} 0 R\n", contents_object_id));
+    /// Render a `Scene` — a display list of filled/stroked paths, each with
+    /// an optional clip — onto a single page of the given size, handling the
+    /// coordinate flip and colorspace setup that `render_page` already does.
+    pub fn render_scene(&mut self, size: (f32, f32), scene: &Scene) -> io::Result<()> {
+        let (width, height) = size;
+        self.render_page(width, height, |canvas| {
+            for item in scene {
+                try!(canvas.save_state());
+                if let Some(ref clip_path) = item.clip {
+                    try!(apply_path(canvas, clip_path));
+                    try!(canvas.clip(item.fill_rule));
+                }
+                try!(apply_path(canvas, &item.path));
+                match item.paint {
+                    Paint::Fill { color: (r, g, b) } => {
+                        try!(canvas.set_fill_color(r, g, b));
+                        try!(canvas.fill_with_rule(item.fill_rule));
+                    }
+                    Paint::Stroke { color: (r, g, b), line_width } => {
+                        try!(canvas.set_stroke_color(r, g, b));
+                        try!(canvas.set_line_width(line_width));
+                        try!(canvas.stroke());
+                    }
+                    Paint::FillAndStroke { fill_color: (fr, fg, fb), stroke_color: (sr, sg, sb),
+                                           line_width } => {
+                        try!(canvas.set_fill_color(fr, fg, fb));
+                        try!(canvas.set_stroke_color(sr, sg, sb));
+                        try!(canvas.set_line_width(line_width));
+                        try!(canvas.fill_and_stroke_with_rule(item.fill_rule));
+                    }
+                }
+                try!(canvas.restore_state());
+            }
+            Ok(())
+        })
+    }
+
+    fn render_page_impl<F>(&mut self, width: f32, height: f32, rotate: u16, render_contents: F)
+                            -> io::Result<()>
+    where F: FnOnce(&mut Canvas<Vec<u8>>) -> io::Result<()> {
+        assert!(rotate == 0 || rotate == 90 || rotate == 180 || rotate == 270,
+                "/Rotate must be 0, 90, 180, or 270 degrees");
+        let mut content = Vec::new();
+        try!(write!(content, "/DeviceRGB cs /DeviceRGB CS\n"));
+        try!(write!(content, "0.75 0 0 -0.75 0 {} cm\n", height));
+        let mut canvas = Canvas { output: &mut content, fonts_used: vec![], current_font: None };
+        try!(render_contents(&mut canvas));
+        let fonts_used = canvas.fonts_used;
+
+        let compress = self.compress;
+        let contents_object_id = try!(self.write_new_object(move |contents_object_id, pdf| {
+            let content = if compress { try!(deflate_bytes(&content)) } else { content };
+            if compress {
+                try!(write!(pdf.output, "<<  /Filter /FlateDecode\n"));
+                try!(write!(pdf.output, "    /Length {}\n", content.len()));
+            } else {
+                try!(write!(pdf.output, "<<  /Length {}\n", content.len()));
+            }
             try!(write!(pdf.output, ">>\n"));
-            Ok(page_object_id)
+            try!(write!(pdf.output, "stream\n"));
+            try!(pdf.output.write_all(&content));
+            try!(write!(pdf.output, "\nendstream\n"));
+            Ok(contents_object_id)
+        }));
+        let page_object_id = try!(self.write_new_dict_object(|| {
+            let mut dict = Vec::new();
+            try!(write!(dict, "<<  /Type /Page\n"));
+            try!(write!(dict, "    /Parent {} 0 R\n", PAGES_OBJECT_ID));
+            try!(write!(dict, "    /Resources << "));
+            if !fonts_used.is_empty() {
+                try!(write!(dict, "/Font << "));
+                for &font_object_id in &fonts_used {
+                    try!(write!(dict, "/F{} {} 0 R ", font_object_id, font_object_id));
+                }
+                try!(write!(dict, ">> "));
+            }
+            try!(write!(dict, ">>\n"));
+            try!(write!(dict, "    /MediaBox [ 0 0 {} {} ]\n", width, height));
+            if rotate != 0 {
+                try!(write!(dict, "    /Rotate {}\n", rotate));
+            }
+            try!(write!(dict, "    /Contents {} 0 R\n", contents_object_id));
+            try!(write!(dict, ">>\n"));
+            Ok(dict)
         }));
         self.page_objects_ids.push(page_object_id);
         Ok(())
     }
 
+    /// Embed a TrueType/OpenType font program and register it as a PDF font object.
+    ///
+    /// `base_font` is used verbatim as the `/BaseFont` name. Glyph widths are
+    /// approximated uniformly; this is good enough for rendering but not for
+    /// precise layout.
+    pub fn add_font(&mut self, base_font: &str, program: &[u8]) -> io::Result<Font> {
+        let file_object_id = try!(self.write_new_object(|file_object_id, pdf| {
+            try!(write!(pdf.output, "<<  /Length {}\n", program.len()));
+            try!(write!(pdf.output, "    /Length1 {}\n", program.len()));
+            try!(write!(pdf.output, ">>\n"));
+            try!(write!(pdf.output, "stream\n"));
+            try!(pdf.output.write_all(program));
+            try!(write!(pdf.output, "\nendstream\n"));
+            Ok(file_object_id)
+        }));
+        let descriptor_object_id = try!(self.write_new_dict_object(|| {
+            let mut dict = Vec::new();
+            try!(write!(dict, "<<  /Type /FontDescriptor\n"));
+            try!(write!(dict, "    /FontName /{}\n", base_font));
+            // Bit 6 (Nonsymbolic): the font uses the standard Latin character set.
+            try!(write!(dict, "    /Flags 32\n"));
+            try!(write!(dict, "    /FontBBox [ -1000 -1000 2000 2000 ]\n"));
+            try!(write!(dict, "    /ItalicAngle 0\n"));
+            try!(write!(dict, "    /Ascent 1000\n"));
+            try!(write!(dict, "    /Descent -200\n"));
+            try!(write!(dict, "    /StemV 80\n"));
+            try!(write!(dict, "    /FontFile2 {} 0 R\n", file_object_id));
+            try!(write!(dict, ">>\n"));
+            Ok(dict)
+        }));
+        let font_object_id = try!(self.write_new_dict_object(|| {
+            let mut dict = Vec::new();
+            try!(write!(dict, "<<  /Type /Font\n"));
+            try!(write!(dict, "    /Subtype /TrueType\n"));
+            try!(write!(dict, "    /BaseFont /{}\n", base_font));
+            try!(write!(dict, "    /Encoding /WinAnsiEncoding\n"));
+            try!(write!(dict, "    /FirstChar {}\n", FIRST_CHAR));
+            try!(write!(dict, "    /LastChar {}\n", LAST_CHAR));
+            try!(write!(dict, "    /Widths [ "));
+            for _ in 0..(LAST_CHAR as usize - FIRST_CHAR as usize + 1) {
+                try!(write!(dict, "{} ", DEFAULT_GLYPH_WIDTH));
+            }
+            try!(write!(dict, "]\n"));
+            try!(write!(dict, "    /FontDescriptor {} 0 R\n", descriptor_object_id));
+            try!(write!(dict, ">>\n"));
+            Ok(dict)
+        }));
+        Ok(Font { object_id: font_object_id })
+    }
+
+    /// Write a new object whose content is a PDF stream; these cannot be
+    /// packed into an object stream, so they always get a byte offset.
     fn write_new_object<F, T>(&mut self, write_content: F) -> io::Result<T>
     where F: FnOnce(usize, &mut Pdf<W>) -> io::Result<T> {
         let id = self.object_offsets.len();
         let (result, offset) = try!(self.write_object(id, |pdf| write_content(id, pdf)));
-        self.object_offsets.push(offset);
+        self.object_offsets.push(ObjectLocation::Offset(offset));
         Ok(result)
     }
 
-    fn write_object_with_id<F, T>(&mut self, id: usize, write_content: F) -> io::Result<T>
+    fn write_object<F, T>(&mut self, id: usize, write_content: F) -> io::Result<(T, u64)>
     where F: FnOnce(&mut Pdf<W>) -> io::Result<T> {
-        assert!(self.object_offsets[id] == -1);
-        let (result, offset) = try!(self.write_object(id, write_content));
-        self.object_offsets[id] = offset;
-        Ok(result)
-    }
-
-    fn write_object<F, T>(&mut self, id: usize, write_content: F) -> io::Result<(T, i64)>
-    where F: FnOnce(&mut Pdf<W>) -> io::Result<T> {
-        // `as i64` here would only overflow for PDF files bigger than 2**63 bytes
-        let offset = try!(self.tell()) as i64;
+        let offset = try!(self.tell());
         try!(write!(self.output, "{} 0 obj\n", id));
         let result = try!(write_content(self));
         try!(write!(self.output, "endobj\n"));
         Ok((result, offset))
     }
 
+    /// Allocate a new object whose content is a plain dictionary (no stream).
+    /// When cross-reference streams are in use, its content is buffered and
+    /// later packed into an `/Type /ObjStm` object by `finish`; otherwise it
+    /// is written immediately like any other object.
+    fn write_new_dict_object<F>(&mut self, write_content: F) -> io::Result<usize>
+    where F: FnOnce() -> io::Result<Vec<u8>> {
+        let id = self.object_offsets.len();
+        self.object_offsets.push(ObjectLocation::Unwritten);
+        try!(self.write_dict_object_with_id(id, write_content));
+        Ok(id)
+    }
+
+    fn write_dict_object_with_id<F>(&mut self, id: usize, write_content: F) -> io::Result<()>
+    where F: FnOnce() -> io::Result<Vec<u8>> {
+        let dict = try!(write_content());
+        if self.use_xref_streams {
+            self.pending_objstm.push((id, dict));
+        } else {
+            let offset = try!(self.tell());
+            try!(write!(self.output, "{} 0 obj\n", id));
+            try!(self.output.write_all(&dict));
+            try!(write!(self.output, "endobj\n"));
+            self.object_offsets[id] = ObjectLocation::Offset(offset);
+        }
+        Ok(())
+    }
+
     pub fn finish(mut self) -> io::Result<()> {
-        try!(self.write_object_with_id(PAGES_OBJECT_ID, |pdf| {
-            try!(write!(pdf.output, "<<  /Type /Pages\n"));
-            try!(write!(pdf.output, "    /Count {}\n", pdf.page_objects_ids.len()));
-            try!(write!(pdf.output, "    /Kids [ "));
-            for &page_object_id in &pdf.page_objects_ids {
-                try!(write!(pdf.output, "{} 0 R ", page_object_id));
+        // Clone out of `self` so the closures below don’t need to borrow `self.info`
+        // while also taking `&mut self` to write the objects.
+        let info = self.info.clone();
+        let info_object_id = if let Some(ref info) = info {
+            Some(try!(self.write_new_dict_object(|| {
+                let mut dict = Vec::new();
+                try!(write!(dict, "<<  "));
+                if let Some(ref s) = info.title {
+                    try!(write!(dict, "/Title ("));
+                    try!(dict.write_all(&encode_pdf_text_string(s)));
+                    try!(write!(dict, ")\n    "));
+                }
+                if let Some(ref s) = info.author {
+                    try!(write!(dict, "/Author ("));
+                    try!(dict.write_all(&encode_pdf_text_string(s)));
+                    try!(write!(dict, ")\n    "));
+                }
+                if let Some(ref s) = info.subject {
+                    try!(write!(dict, "/Subject ("));
+                    try!(dict.write_all(&encode_pdf_text_string(s)));
+                    try!(write!(dict, ")\n    "));
+                }
+                if let Some(ref s) = info.keywords {
+                    try!(write!(dict, "/Keywords ("));
+                    try!(dict.write_all(&encode_pdf_text_string(s)));
+                    try!(write!(dict, ")\n    "));
+                }
+                if let Some(ref s) = info.creator {
+                    try!(write!(dict, "/Creator ("));
+                    try!(dict.write_all(&encode_pdf_text_string(s)));
+                    try!(write!(dict, ")\n    "));
+                }
+                if let Some(ref s) = info.producer {
+                    try!(write!(dict, "/Producer ("));
+                    try!(dict.write_all(&encode_pdf_text_string(s)));
+                    try!(write!(dict, ")\n    "));
+                }
+                if let Some(ref date) = info.creation_date {
+                    try!(write!(dict, "/CreationDate ({})\n    ", date.to_pdf_string()));
+                }
+                if let Some(ref date) = info.mod_date {
+                    try!(write!(dict, "/ModDate ({})\n    ", date.to_pdf_string()));
+                }
+                try!(write!(dict, ">>\n"));
+                Ok(dict)
+            })))
+        } else {
+            None
+        };
+        let metadata_object_id = if let Some(ref info) = info {
+            let xmp = build_xmp_packet(info);
+            Some(try!(self.write_new_object(|metadata_object_id, pdf| {
+                try!(write!(pdf.output, "<<  /Type /Metadata\n"));
+                try!(write!(pdf.output, "    /Subtype /XML\n"));
+                try!(write!(pdf.output, "    /Length {}\n", xmp.len()));
+                try!(write!(pdf.output, ">>\n"));
+                try!(write!(pdf.output, "stream\n"));
+                try!(pdf.output.write_all(xmp.as_bytes()));
+                try!(write!(pdf.output, "\nendstream\n"));
+                Ok(metadata_object_id)
+            })))
+        } else {
+            None
+        };
+        let page_objects_ids = self.page_objects_ids.clone();
+        try!(self.write_dict_object_with_id(PAGES_OBJECT_ID, || {
+            let mut dict = Vec::new();
+            try!(write!(dict, "<<  /Type /Pages\n"));
+            try!(write!(dict, "    /Count {}\n", page_objects_ids.len()));
+            try!(write!(dict, "    /Kids [ "));
+            for &page_object_id in &page_objects_ids {
+                try!(write!(dict, "{} 0 R ", page_object_id));
             }
-            try!(write!(pdf.output, "]\n"));
-            try!(write!(pdf.output, ">>\n"));
-            Ok(())
+            try!(write!(dict, "]\n"));
+            try!(write!(dict, ">>\n"));
+            Ok(dict)
+        }));
+        try!(self.write_dict_object_with_id(ROOT_OBJECT_ID, || {
+            let mut dict = Vec::new();
+            try!(write!(dict, "<<  /Type /Catalog\n"));
+            try!(write!(dict, "    /Pages {} 0 R\n", PAGES_OBJECT_ID));
+            if let Some(metadata_object_id) = metadata_object_id {
+                try!(write!(dict, "    /Metadata {} 0 R\n", metadata_object_id));
+            }
+            try!(write!(dict, ">>\n"));
+            Ok(dict)
         }));
-        try!(self.write_object_with_id(ROOT_OBJECT_ID, |pdf| {
-            try!(write!(pdf.output, "<<  /Type /Catalog\n"));
-            try!(write!(pdf.output, "    /Pages {} 0 R\n", PAGES_OBJECT_ID));
+        if self.use_xref_streams {
+            try!(self.finish_with_xref_stream(info_object_id));
+        } else {
+            try!(self.finish_with_xref_table(info_object_id));
+        }
+        Ok(())
+    }
+
+    /// Pack every buffered dictionary object into a single `/Type /ObjStm`
+    /// object, recording each one's compressed location.
+    fn flush_pending_objstm(&mut self) -> io::Result<()> {
+        if self.pending_objstm.is_empty() {
+            return Ok(());
+        }
+        let entries = ::std::mem::replace(&mut self.pending_objstm, vec![]);
+        let mut header = Vec::new();
+        let mut body = Vec::new();
+        for &(id, ref dict) in &entries {
+            try!(write!(header, "{} {} ", id, body.len()));
+            try!(body.write_all(dict));
+        }
+        let first = header.len();
+        let mut uncompressed = header;
+        try!(uncompressed.write_all(&body));
+        let n = entries.len();
+        let objstm_object_id = try!(self.write_new_object(move |objstm_object_id, pdf| {
+            let compressed = try!(deflate_bytes(&uncompressed));
+            try!(write!(pdf.output, "<<  /Type /ObjStm\n"));
+            try!(write!(pdf.output, "    /N {}\n", n));
+            try!(write!(pdf.output, "    /First {}\n", first));
+            try!(write!(pdf.output, "    /Filter /FlateDecode\n"));
+            try!(write!(pdf.output, "    /Length {}\n", compressed.len()));
             try!(write!(pdf.output, ">>\n"));
-            Ok(())
+            try!(write!(pdf.output, "stream\n"));
+            try!(pdf.output.write_all(&compressed));
+            try!(write!(pdf.output, "\nendstream\n"));
+            Ok(objstm_object_id)
         }));
+        for (index, &(id, _)) in entries.iter().enumerate() {
+            self.object_offsets[id] = ObjectLocation::Compressed {
+                stream_object_id: objstm_object_id,
+                index: index,
+            };
+        }
+        Ok(())
+    }
+
+    fn finish_with_xref_table(&mut self, info_object_id: Option<usize>) -> io::Result<()> {
         let startxref = try!(self.tell());
         try!(write!(self.output, "xref\n"));
         try!(write!(self.output, "0 {}\n", self.object_offsets.len()));
         // Object 0 is special
         try!(write!(self.output, "0000000000 65535 f \n"));
         // Use [1..] to skip object 0 in self.object_offsets.
-        for &offset in &self.object_offsets[1..] {
-            assert!(offset >= 0);
-            try!(write!(self.output, "{:010} 00000 n \n", offset));
+        for &location in &self.object_offsets[1..] {
+            match location {
+                ObjectLocation::Offset(offset) => {
+                    try!(write!(self.output, "{:010} 00000 n \n", offset));
+                }
+                ObjectLocation::Unwritten | ObjectLocation::Compressed { .. } => {
+                    panic!("every object should have a byte offset without cross-reference streams");
+                }
+            }
         }
         try!(write!(self.output, "trailer\n"));
         try!(write!(self.output, "<<  /Size {}\n", self.object_offsets.len()));
         try!(write!(self.output, "    /Root {} 0 R\n", ROOT_OBJECT_ID));
+        if let Some(info_object_id) = info_object_id {
+            try!(write!(self.output, "    /Info {} 0 R\n", info_object_id));
+        }
+        try!(write!(self.output, ">>\n"));
+        try!(write!(self.output, "startxref\n"));
+        try!(write!(self.output, "{}\n", startxref));
+        try!(write!(self.output, "%%EOF\n"));
+        Ok(())
+    }
+
+    /// Write a PDF 1.5 `/Type /XRef` cross-reference stream in place of the
+    /// classic `xref` table and `trailer` dictionary.
+    fn finish_with_xref_stream(&mut self, info_object_id: Option<usize>) -> io::Result<()> {
+        try!(self.flush_pending_objstm());
+
+        // The XRef stream object refers to itself, so reserve its ID before
+        // building the records (its own location is simply its own offset).
+        let xref_object_id = self.object_offsets.len();
+        self.object_offsets.push(ObjectLocation::Unwritten);
+
+        let size = self.object_offsets.len();
+        let startxref = try!(self.tell());
+        let records = try!(build_xref_stream_records(&self.object_offsets, xref_object_id,
+                                                       startxref));
+
+        let compressed = try!(deflate_bytes(&records));
+        try!(write!(self.output, "{} 0 obj\n", xref_object_id));
+        try!(write!(self.output, "<<  /Type /XRef\n"));
+        try!(write!(self.output, "    /Size {}\n", size));
+        try!(write!(self.output, "    /Root {} 0 R\n", ROOT_OBJECT_ID));
+        if let Some(info_object_id) = info_object_id {
+            try!(write!(self.output, "    /Info {} 0 R\n", info_object_id));
+        }
+        try!(write!(self.output, "    /W [ 1 4 2 ]\n"));
+        try!(write!(self.output, "    /Filter /FlateDecode\n"));
+        try!(write!(self.output, "    /Length {}\n", compressed.len()));
         try!(write!(self.output, ">>\n"));
+        try!(write!(self.output, "stream\n"));
+        try!(self.output.write_all(&compressed));
+        try!(write!(self.output, "\nendstream\n"));
+        try!(write!(self.output, "endobj\n"));
         try!(write!(self.output, "startxref\n"));
         try!(write!(self.output, "{}\n", startxref));
         try!(write!(self.output, "%%EOF\n"));
@@ -133,10 +668,327 @@ impl<'a, W: Write + Seek> Pdf<'a, W> {
 }
 
 impl<'a, W: Write> Canvas<'a, W> {
-    pub fn rectangle(&mut self, r: u8, g: u8, b: u8, x: f32, y: f32, width: f32, height: f32)
+    /// Fill an axis-aligned rectangle, using `r`/`g`/`b` as normalized
+    /// `0.0..=1.0` DeviceRGB components.
+    pub fn rectangle(&mut self, r: f32, g: f32, b: f32, x: f32, y: f32, width: f32, height: f32)
                      -> io::Result<()> {
-        write!(self.output, "{} {} {} sc {} {} {} {} re f\n",
+        write!(self.output, "{} {} {} rg {} {} {} {} re f\n",
                r, g, b,
                x, y, width, height)
     }
+
+    /// Select the font and size used by subsequent calls to `text`.
+    pub fn set_font(&mut self, font: Font, size: f32) {
+        if !self.fonts_used.contains(&font.object_id) {
+            self.fonts_used.push(font.object_id);
+        }
+        self.current_font = Some((font.object_id, size));
+    }
+
+    /// Draw `text` with its baseline starting at `(x, y)`, using the font and
+    /// size set by the most recent call to `set_font`.
+    ///
+    /// Panics if `set_font` has not been called yet.
+    pub fn text(&mut self, x: f32, y: f32, text: &str) -> io::Result<()> {
+        let (font_object_id, size) = self.current_font
+            .expect("Canvas::set_font must be called before Canvas::text");
+        try!(write!(self.output, "BT /F{} {} Tf {} {} Td (",
+                    font_object_id, size, x, y));
+        try!(self.output.write_all(&escape_pdf_bytes(&encode_winansi(text))));
+        write!(self.output, ") Tj ET\n")
+    }
+
+    /// Begin a new subpath at `(x, y)`.
+    pub fn move_to(&mut self, x: f32, y: f32) -> io::Result<()> {
+        write!(self.output, "{} {} m\n", x, y)
+    }
+
+    /// Append a straight line segment from the current point to `(x, y)`.
+    pub fn line_to(&mut self, x: f32, y: f32) -> io::Result<()> {
+        write!(self.output, "{} {} l\n", x, y)
+    }
+
+    /// Append a cubic Bézier curve from the current point to `(x3, y3)`,
+    /// using `(x1, y1)` and `(x2, y2)` as control points.
+    pub fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x3: f32, y3: f32)
+                     -> io::Result<()> {
+        write!(self.output, "{} {} {} {} {} {} c\n", x1, y1, x2, y2, x3, y3)
+    }
+
+    /// Close the current subpath with a straight line back to its start.
+    pub fn close_path(&mut self) -> io::Result<()> {
+        write!(self.output, "h\n")
+    }
+
+    /// Fill the current path using the nonzero winding rule.
+    pub fn fill(&mut self) -> io::Result<()> {
+        write!(self.output, "f\n")
+    }
+
+    /// Stroke the current path.
+    pub fn stroke(&mut self) -> io::Result<()> {
+        write!(self.output, "S\n")
+    }
+
+    /// Fill then stroke the current path.
+    pub fn fill_and_stroke(&mut self) -> io::Result<()> {
+        write!(self.output, "B\n")
+    }
+
+    /// Fill the current path using the given winding rule.
+    pub fn fill_with_rule(&mut self, rule: FillRule) -> io::Result<()> {
+        write!(self.output, "{}\n", match rule {
+            FillRule::NonZero => "f",
+            FillRule::EvenOdd => "f*",
+        })
+    }
+
+    /// Fill then stroke the current path, using the given winding rule to fill.
+    pub fn fill_and_stroke_with_rule(&mut self, rule: FillRule) -> io::Result<()> {
+        write!(self.output, "{}\n", match rule {
+            FillRule::NonZero => "B",
+            FillRule::EvenOdd => "B*",
+        })
+    }
+
+    /// Intersect the clipping path with the current path, using the given
+    /// winding rule, then discard the path without painting it.
+    pub fn clip(&mut self, rule: FillRule) -> io::Result<()> {
+        write!(self.output, "{} n\n", match rule {
+            FillRule::NonZero => "W",
+            FillRule::EvenOdd => "W*",
+        })
+    }
+
+    /// Save the current graphics state.
+    pub fn save_state(&mut self) -> io::Result<()> {
+        write!(self.output, "q\n")
+    }
+
+    /// Restore the graphics state previously saved by `save_state`.
+    pub fn restore_state(&mut self) -> io::Result<()> {
+        write!(self.output, "Q\n")
+    }
+
+    /// Set the line width used by subsequent `stroke` calls.
+    pub fn set_line_width(&mut self, width: f32) -> io::Result<()> {
+        write!(self.output, "{} w\n", width)
+    }
+
+    /// Set the stroke color, as normalized `0.0..=1.0` DeviceRGB components.
+    pub fn set_stroke_color(&mut self, r: f32, g: f32, b: f32) -> io::Result<()> {
+        write!(self.output, "{} {} {} RG\n", r, g, b)
+    }
+
+    /// Set the fill color, as normalized `0.0..=1.0` DeviceRGB components.
+    pub fn set_fill_color(&mut self, r: f32, g: f32, b: f32) -> io::Result<()> {
+        write!(self.output, "{} {} {} rg\n", r, g, b)
+    }
+}
+
+/// Encode `s` for use as a PDF text string (e.g. in `/Info`): ASCII text is
+/// written as-is, since it is valid PDFDocEncoding; text with non-ASCII
+/// characters is written as UTF-16BE with a leading byte-order mark, per the
+/// PDF text string rules, so readers don't mistake it for single-byte
+/// PDFDocEncoding (or, worse, misinterpret its raw UTF-8 bytes). Either way
+/// the result still needs `(`, `)`, and `\` escaped for the literal-string
+/// syntax, which `escape_pdf_bytes` does.
+fn encode_pdf_text_string(s: &str) -> Vec<u8> {
+    if s.is_ascii() {
+        escape_pdf_bytes(s.as_bytes())
+    } else {
+        let mut bytes = vec![0xFE, 0xFF];
+        for unit in s.encode_utf16() {
+            bytes.push((unit >> 8) as u8);
+            bytes.push((unit & 0xFF) as u8);
+        }
+        escape_pdf_bytes(&bytes)
+    }
+}
+
+/// Escape `(`, `)`, and `\` in a byte string destined for a PDF literal string.
+fn escape_pdf_bytes(bytes: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(bytes.len());
+    for &b in bytes {
+        if b == b'(' || b == b')' || b == b'\\' {
+            escaped.push(b'\\');
+        }
+        escaped.push(b);
+    }
+    escaped
+}
+
+/// Encode `s` to single-byte WinAnsiEncoding (Windows-1252), the encoding
+/// declared on the font object in `add_font`. Characters with no WinAnsi
+/// code point are replaced with `?`.
+fn encode_winansi(s: &str) -> Vec<u8> {
+    s.chars().map(|c| {
+        let code = c as u32;
+        match code {
+            0x20...0x7E | 0xA0...0xFF => code as u8,
+            0x20AC => 0x80, // EURO SIGN
+            0x201A => 0x82, // SINGLE LOW-9 QUOTATION MARK
+            0x0192 => 0x83, // LATIN SMALL LETTER F WITH HOOK
+            0x201E => 0x84, // DOUBLE LOW-9 QUOTATION MARK
+            0x2026 => 0x85, // HORIZONTAL ELLIPSIS
+            0x2020 => 0x86, // DAGGER
+            0x2021 => 0x87, // DOUBLE DAGGER
+            0x02C6 => 0x88, // MODIFIER LETTER CIRCUMFLEX ACCENT
+            0x2030 => 0x89, // PER MILLE SIGN
+            0x0160 => 0x8A, // LATIN CAPITAL LETTER S WITH CARON
+            0x2039 => 0x8B, // SINGLE LEFT-POINTING ANGLE QUOTATION MARK
+            0x0152 => 0x8C, // LATIN CAPITAL LIGATURE OE
+            0x017D => 0x8E, // LATIN CAPITAL LETTER Z WITH CARON
+            0x2018 => 0x91, // LEFT SINGLE QUOTATION MARK
+            0x2019 => 0x92, // RIGHT SINGLE QUOTATION MARK
+            0x201C => 0x93, // LEFT DOUBLE QUOTATION MARK
+            0x201D => 0x94, // RIGHT DOUBLE QUOTATION MARK
+            0x2022 => 0x95, // BULLET
+            0x2013 => 0x96, // EN DASH
+            0x2014 => 0x97, // EM DASH
+            0x02DC => 0x98, // SMALL TILDE
+            0x2122 => 0x99, // TRADE MARK SIGN
+            0x0161 => 0x9A, // LATIN SMALL LETTER S WITH CARON
+            0x203A => 0x9B, // SINGLE RIGHT-POINTING ANGLE QUOTATION MARK
+            0x0153 => 0x9C, // LATIN SMALL LIGATURE OE
+            0x017E => 0x9E, // LATIN SMALL LETTER Z WITH CARON
+            0x0178 => 0x9F, // LATIN CAPITAL LETTER Y WITH DIAERESIS
+            _ => b'?',
+        }
+    }).collect()
+}
+
+/// Append `value` to `buf` as a fixed-width big-endian integer, for an
+/// `/XRef` stream record field.
+fn write_be(buf: &mut Vec<u8>, value: u64, width: usize) -> io::Result<()> {
+    for i in (0..width).rev() {
+        buf.push(((value >> (8 * i)) & 0xFF) as u8);
+    }
+    Ok(())
+}
+
+/// Build the uncompressed body of a `/Type /XRef` stream (one fixed-width
+/// `/W [ 1 4 2 ]` record per object, in `object_offsets` order), patching in
+/// `xref_offset` as the byte offset of `object_offsets[xref_object_id]`,
+/// which is still `Unwritten` since the XRef stream object refers to itself.
+fn build_xref_stream_records(object_offsets: &[ObjectLocation], xref_object_id: usize,
+                              xref_offset: u64) -> io::Result<Vec<u8>> {
+    let mut records = Vec::new();
+    // Object 0 is special: free, with generation 65535.
+    records.push(0u8);
+    try!(write_be(&mut records, 0, 4));
+    try!(write_be(&mut records, 65535, 2));
+    for index in 1..object_offsets.len() {
+        match object_offsets[index] {
+            ObjectLocation::Offset(offset) => {
+                records.push(1);
+                try!(write_be(&mut records, offset, 4));
+                try!(write_be(&mut records, 0, 2));
+            }
+            ObjectLocation::Compressed { stream_object_id, index: objstm_index } => {
+                records.push(2);
+                try!(write_be(&mut records, stream_object_id as u64, 4));
+                try!(write_be(&mut records, objstm_index as u64, 2));
+            }
+            ObjectLocation::Unwritten => {
+                // Only the XRef stream object itself should still be
+                // unwritten at this point; its offset is patched in below.
+                records.push(1);
+                try!(write_be(&mut records, 0, 4));
+                try!(write_be(&mut records, 0, 2));
+            }
+        }
+    }
+
+    // Patch in the offset of the XRef stream object, at its own record.
+    let mut patched_offset = Vec::new();
+    try!(write_be(&mut patched_offset, xref_offset, 4));
+    let offset_bytes_start = 7 * xref_object_id + 1;
+    records[offset_bytes_start..offset_bytes_start + 4].copy_from_slice(&patched_offset);
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ObjectLocation, build_xref_stream_records};
+
+    #[test]
+    fn xref_stream_records_match_object_offsets() {
+        let object_offsets = vec![
+            ObjectLocation::Unwritten, // object 0: unused, the loop always writes it as free.
+            ObjectLocation::Offset(15),
+            ObjectLocation::Compressed { stream_object_id: 5, index: 0 },
+            ObjectLocation::Compressed { stream_object_id: 5, index: 1 },
+            ObjectLocation::Unwritten, // object 4: the XRef stream object itself.
+        ];
+        let records = build_xref_stream_records(&object_offsets, 4, 999).unwrap();
+
+        // One 7-byte record (`/W [ 1 4 2 ]`) per object, 0 through 4.
+        assert_eq!(records.len(), 5 * 7);
+        assert_eq!(&records[0..7], &[0, 0, 0, 0, 0, 0xFF, 0xFF]);
+        assert_eq!(&records[7..14], &[1, 0, 0, 0, 15, 0, 0]);
+        assert_eq!(&records[14..21], &[2, 0, 0, 0, 5, 0, 0]);
+        assert_eq!(&records[21..28], &[2, 0, 0, 0, 5, 0, 1]);
+        assert_eq!(&records[28..35], &[1, 0, 0, 3, 0xE7, 0, 0]);
+    }
+}
+
+/// Compress `data` as a zlib stream, suitable for a `/Filter /FlateDecode` object.
+fn deflate_bytes(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::Default);
+    try!(encoder.write_all(data));
+    encoder.finish()
+}
+
+/// Build a minimal XMP packet exposing the same metadata as the `/Info` dictionary.
+fn build_xmp_packet(info: &Info) -> String {
+    let mut xmp = String::new();
+    xmp.push_str("<?xpacket begin=\"\u{FEFF}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n");
+    xmp.push_str("<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n");
+    xmp.push_str("<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n");
+    xmp.push_str("<rdf:Description rdf:about=\"\"\n");
+    xmp.push_str("    xmlns:dc=\"http://purl.org/dc/elements/1.1/\"\n");
+    xmp.push_str("    xmlns:pdf=\"http://ns.adobe.com/pdf/1.3/\"\n");
+    xmp.push_str("    xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\">\n");
+    if let Some(ref title) = info.title {
+        xmp.push_str(&format!("  <dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li>\
+                               </rdf:Alt></dc:title>\n", escape_xml(title)));
+    }
+    if let Some(ref author) = info.author {
+        xmp.push_str(&format!("  <dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>\n",
+                               escape_xml(author)));
+    }
+    if let Some(ref subject) = info.subject {
+        xmp.push_str(&format!("  <dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li>\
+                               </rdf:Alt></dc:description>\n", escape_xml(subject)));
+    }
+    if let Some(ref producer) = info.producer {
+        xmp.push_str(&format!("  <pdf:Producer>{}</pdf:Producer>\n", escape_xml(producer)));
+    }
+    if let Some(ref date) = info.creation_date {
+        xmp.push_str(&format!("  <xmp:CreateDate>{}</xmp:CreateDate>\n", date.to_xmp_string()));
+    }
+    if let Some(ref date) = info.mod_date {
+        xmp.push_str(&format!("  <xmp:ModifyDate>{}</xmp:ModifyDate>\n", date.to_xmp_string()));
+    }
+    xmp.push_str("</rdf:Description>\n");
+    xmp.push_str("</rdf:RDF>\n");
+    xmp.push_str("</x:xmpmeta>\n");
+    xmp.push_str("<?xpacket end=\"w\"?>");
+    xmp
+}
+
+/// Escape `&`, `<`, and `>` so that `s` can be written as XML text content.
+fn escape_xml(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
 }